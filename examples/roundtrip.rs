@@ -0,0 +1,51 @@
+extern crate rand;
+extern crate vorbis_enc;
+
+use std::iter;
+use rand::Rng;
+use vorbis_enc::{OggVorbisEncoder, OggVorbisDecoder};
+
+fn main() {
+
+    let mut rng = rand::thread_rng();
+
+    let mut encoder = OggVorbisEncoder::new("roundtrip.ogg").unwrap();
+    encoder.set_comment("TITLE", "Roundtrip Noise").ok();
+    encoder.initialize_with_vbr(2, 48000, 0.2).ok();
+
+    let mut samples: Vec<i16> = iter::repeat(0).take((32558) as usize).collect();
+    for i in 0..samples.len() / 2 {
+        samples[i * 2] = ((rng.next_f32() - 0.5) * u16::max_value() as f32) as i16;
+        samples[i * 2 + 1] = ((rng.next_f32() - 0.5) * u16::max_value() as f32) as i16;
+    }
+
+    let mut packets = 0;
+    while packets < 64 {
+        encoder.write_samples(&samples).ok();
+        packets += 1;
+    }
+
+    encoder.close().ok();
+    println!("{} bytes of noise written.", encoder.len());
+
+    let mut decoder = OggVorbisDecoder::open("roundtrip.ogg").unwrap();
+    let decoded = decoder.read_samples().unwrap();
+
+    println!(
+        "decoded {} samples, {} channels, {} Hz",
+        decoded.len(),
+        decoder.channels(),
+        decoder.sample_rate()
+    );
+
+    assert_eq!(decoder.channels(), 2);
+    assert_eq!(decoder.sample_rate(), 48000);
+    assert!(decoded.len() > 0, "decoded no samples");
+    assert_eq!(
+        decoder.comments().iter().find(|&&(ref k, _)| k == "TITLE").map(|&(_, ref v)| v.as_str()),
+        Some("Roundtrip Noise")
+    );
+
+    println!("round trip ok.");
+
+}
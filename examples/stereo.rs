@@ -12,7 +12,7 @@ fn main() {
     let mut encoder = OggVorbisEncoder::new("stereo.ogg").unwrap();
     encoder.initialize_with_vbr(2, 48000, 0.2).ok();
 
-    let mut samples: Vec<i16> = iter::repeat(0).take((32559) as usize).collect();
+    let mut samples: Vec<i16> = iter::repeat(0).take((32558) as usize).collect();
     for i in 0..samples.len() / 2 {
         samples[i * 2] = ((rng.next_f32() - 0.5) * u16::max_value() as f32) as i16;
         samples[i * 2 + 1] = 0;
@@ -0,0 +1,444 @@
+// Copyright (c) 2016 Ivo Wetzel
+
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// STD Dependencies -----------------------------------------------------------
+use std;
+use std::mem;
+use std::ptr;
+use std::fs::File;
+use std::io::Read;
+
+
+// Vorbis Dependencies --------------------------------------------------------
+use vorbis_sys::{
+    vorbis_info,
+    vorbis_comment,
+    vorbis_dsp_state,
+    vorbis_block,
+
+    vorbis_info_init,
+    vorbis_info_clear,
+    vorbis_comment_init,
+    vorbis_comment_clear,
+    vorbis_dsp_clear,
+    vorbis_block_init,
+    vorbis_block_clear
+};
+
+// `vorbis_sys` doesn't have the synthesis-side calls yet, only the
+// analysis side the encoder uses, hence the manual externs below.
+use libc::{c_int, c_long};
+
+extern "C" {
+    fn vorbis_synthesis_headerin(vi: *mut vorbis_info, vc: *mut vorbis_comment, op: *mut ogg_packet) -> c_int;
+    fn vorbis_synthesis_init(vd: *mut vorbis_dsp_state, vi: *mut vorbis_info) -> c_int;
+    fn vorbis_synthesis(vb: *mut vorbis_block, op: *mut ogg_packet) -> c_int;
+    fn vorbis_synthesis_blockin(vd: *mut vorbis_dsp_state, vb: *mut vorbis_block) -> c_int;
+    fn vorbis_synthesis_pcmout(vd: *mut vorbis_dsp_state, pcm: *mut *mut *mut f32) -> c_int;
+    fn vorbis_synthesis_read(vd: *mut vorbis_dsp_state, samples: c_int) -> c_int;
+}
+
+
+// Ogg Dependencies -----------------------------------------------------------
+use ogg_sys::{
+    ogg_sync_state,
+    ogg_stream_state,
+    ogg_page,
+    ogg_packet,
+
+    ogg_sync_init,
+    ogg_sync_clear,
+    ogg_sync_buffer,
+    ogg_sync_wrote,
+    ogg_sync_pageout,
+    ogg_stream_init,
+    ogg_stream_clear,
+    ogg_stream_pagein,
+    ogg_stream_packetout,
+    ogg_page_serialno
+};
+
+
+// Internal Types -------------------------------------------------------------
+#[derive(PartialEq)]
+enum DecoderState {
+    Created,
+    Initialized,
+    Closed
+}
+
+
+// Simple Ogg Vorbis Decoder Implementation -----------------------------------
+
+/// Implementation of a file based ogg-vorbis audio decoder.
+pub struct OggVorbisDecoder {
+    file: File,
+    ogg: Box<OggSyncState>,
+    vorbis: Box<VorbisDecodeState>,
+    state: DecoderState
+}
+
+impl OggVorbisDecoder {
+
+    /// Opens the specified ogg-vorbis file and reads its headers.
+    pub fn open(filename: &str) -> Result<OggVorbisDecoder, String> {
+        match File::open(filename) {
+            Ok(file) => {
+
+                let mut decoder = OggVorbisDecoder {
+                    file: file,
+                    ogg: Box::new(OggSyncState::new()),
+                    vorbis: Box::new(VorbisDecodeState::new()),
+                    state: DecoderState::Created
+                };
+
+                decoder.ogg.init();
+                decoder.read_headers()?;
+
+                decoder.state = DecoderState::Initialized;
+
+                Ok(decoder)
+
+            },
+            Err(e) => Err(format!("{}", e))
+        }
+    }
+
+    /// Returns the number of channels of the decoded stream.
+    pub fn channels(&self) -> usize {
+        self.vorbis.channels
+    }
+
+    /// Returns the sample rate of the decoded stream.
+    pub fn sample_rate(&self) -> u32 {
+        self.vorbis.sample_rate
+    }
+
+    /// Returns the `key`/`value` comment tags embedded in the stream.
+    pub fn comments(&self) -> &[(String, String)] {
+        &self.vorbis.comments
+    }
+
+    /// Decodes and returns all remaining interleaved i16 samples.
+    pub fn read_samples(&mut self) -> Result<Vec<i16>, String> {
+        match self.state {
+            DecoderState::Created => {
+                Err("Audio stream not initialized.".to_string())
+            },
+            DecoderState::Initialized => {
+
+                let mut samples = Vec::new();
+                let mut buffer = [0u8; 4096];
+
+                loop {
+
+                    while let Some(packet) = self.ogg.next_packet() {
+                        self.vorbis.decode_packet(packet, &mut samples);
+                    }
+
+                    let read = self.file.read(&mut buffer).map_err(|e| format!("{}", e))?;
+                    if read == 0 {
+                        break;
+                    }
+
+                    self.ogg.write(&buffer[..read]);
+
+                }
+
+                Ok(samples)
+
+            },
+            DecoderState::Closed => {
+                Err("Audio stream already closed.".to_string())
+            }
+        }
+    }
+
+    /// Closes the audio stream.
+    pub fn close(&mut self) -> Result<(), String> {
+        match self.state {
+            DecoderState::Created => {
+                Err("Audio stream not initialized.".to_string())
+            },
+            DecoderState::Initialized => {
+                self.state = DecoderState::Closed;
+                Ok(())
+            },
+            DecoderState::Closed => {
+                Err("Audio stream already closed.".to_string())
+            }
+        }
+    }
+
+    fn read_headers(&mut self) -> Result<(), String> {
+
+        let mut buffer = [0u8; 4096];
+        let mut headers_read = 0;
+
+        while headers_read < 3 {
+
+            if let Some(packet) = self.ogg.next_packet() {
+                self.vorbis.read_header(packet)?;
+                headers_read += 1;
+                continue;
+            }
+
+            let read = self.file.read(&mut buffer).map_err(|e| format!("{}", e))?;
+            if read == 0 {
+                return Err("Unexpected end of file while reading Vorbis headers.".to_string());
+            }
+
+            self.ogg.write(&buffer[..read]);
+
+        }
+
+        self.vorbis.post_init();
+
+        Ok(())
+
+    }
+
+}
+
+impl Drop for OggVorbisDecoder {
+    fn drop(&mut self) {
+        self.ogg.destroy();
+        self.vorbis.destroy();
+    }
+}
+
+
+// Internal Vorbis Decoding State ----------------------------------------------
+#[repr(C)]
+struct VorbisDecodeState {
+    vi: vorbis_info,
+    vc: vorbis_comment,
+    vd: vorbis_dsp_state,
+    vb: vorbis_block,
+    channels: usize,
+    sample_rate: u32,
+    comments: Vec<(String, String)>,
+    synthesis_ready: bool
+}
+
+impl VorbisDecodeState {
+
+    fn new() -> VorbisDecodeState {
+
+        let mut vi: vorbis_info = unsafe { mem::zeroed() };
+        let mut vc: vorbis_comment = unsafe { mem::zeroed() };
+
+        unsafe {
+            vorbis_info_init(&mut vi);
+            vorbis_comment_init(&mut vc);
+        }
+
+        VorbisDecodeState {
+            vi: vi,
+            vc: vc,
+            vd: unsafe { mem::zeroed() },
+            vb: unsafe { mem::zeroed() },
+            channels: 0,
+            sample_rate: 0,
+            comments: Vec::new(),
+            synthesis_ready: false
+        }
+
+    }
+
+    fn read_header(&mut self, packet: &mut ogg_packet) -> Result<(), String> {
+
+        let result = unsafe {
+            vorbis_synthesis_headerin(&mut self.vi, &mut self.vc, packet)
+        };
+
+        if result < 0 {
+            return Err("Invalid Vorbis header packet.".to_string());
+        }
+
+        self.channels = self.vi.channels as usize;
+        self.sample_rate = self.vi.rate as u32;
+        self.read_comments();
+
+        Ok(())
+
+    }
+
+    fn read_comments(&mut self) {
+
+        self.comments.clear();
+
+        let count = self.vc.comments as usize;
+        if count == 0 {
+            return;
+        }
+
+        unsafe {
+
+            let lengths = std::slice::from_raw_parts(self.vc.comment_lengths, count);
+            let entries = std::slice::from_raw_parts(self.vc.user_comments, count);
+
+            for i in 0..count {
+
+                let bytes = std::slice::from_raw_parts(
+                    entries[i] as *const u8,
+                    lengths[i] as usize
+                );
+
+                if let Ok(entry) = std::str::from_utf8(bytes) {
+                    if let Some(split) = entry.find('=') {
+                        self.comments.push((
+                            entry[..split].to_string(),
+                            entry[split + 1..].to_string()
+                        ));
+                    }
+                }
+
+            }
+
+        }
+
+    }
+
+    fn post_init(&mut self) {
+        unsafe {
+            vorbis_synthesis_init(&mut self.vd, &mut self.vi);
+            vorbis_block_init(&mut self.vd, &mut self.vb);
+        }
+        self.synthesis_ready = true;
+    }
+
+    fn decode_packet(&mut self, packet: &mut ogg_packet, samples: &mut Vec<i16>) {
+
+        if !self.synthesis_ready {
+            return;
+        }
+
+        unsafe {
+
+            if vorbis_synthesis(&mut self.vb, packet) != 0 {
+                return;
+            }
+
+            vorbis_synthesis_blockin(&mut self.vd, &mut self.vb);
+
+            loop {
+
+                let mut pcm: *mut *mut f32 = ptr::null_mut();
+                let frames = vorbis_synthesis_pcmout(&mut self.vd, &mut pcm);
+                if frames <= 0 {
+                    break;
+                }
+
+                let channel_buffers = std::slice::from_raw_parts(pcm, self.channels);
+                for i in 0..frames as usize {
+                    for c in 0..self.channels {
+                        let channel = std::slice::from_raw_parts(channel_buffers[c], frames as usize);
+                        let clamped = (channel[i] * 32768.0).max(-32768.0).min(32767.0);
+                        samples.push(clamped as i16);
+                    }
+                }
+
+                vorbis_synthesis_read(&mut self.vd, frames);
+
+            }
+
+        }
+
+    }
+
+    fn destroy(&mut self) {
+        unsafe {
+            if self.synthesis_ready {
+                vorbis_block_clear(&mut self.vb);
+                vorbis_dsp_clear(&mut self.vd);
+            }
+            vorbis_comment_clear(&mut self.vc);
+            vorbis_info_clear(&mut self.vi);
+        }
+    }
+
+}
+
+
+// Internal Ogg Sync/Stream State ----------------------------------------------
+struct OggSyncState {
+    oy: ogg_sync_state,
+    os: ogg_stream_state,
+    og: ogg_page,
+    op: ogg_packet,
+    stream_started: bool
+}
+
+impl OggSyncState {
+
+    fn new() -> OggSyncState {
+        OggSyncState {
+            oy: unsafe { mem::zeroed() },
+            os: unsafe { mem::zeroed() },
+            og: unsafe { mem::zeroed() },
+            op: unsafe { mem::zeroed() },
+            stream_started: false
+        }
+    }
+
+    fn init(&mut self) {
+        unsafe {
+            ogg_sync_init(&mut self.oy);
+        }
+    }
+
+    fn write(&mut self, data: &[u8]) {
+        unsafe {
+            let target = ogg_sync_buffer(&mut self.oy, data.len() as c_long) as *mut u8;
+            ptr::copy_nonoverlapping(data.as_ptr(), target, data.len());
+            ogg_sync_wrote(&mut self.oy, data.len() as c_long);
+        }
+    }
+
+    /// Pulls the next available Vorbis packet out of the Ogg stream, if any.
+    fn next_packet(&mut self) -> Option<&mut ogg_packet> {
+
+        loop {
+
+            while unsafe { ogg_stream_packetout(&mut self.os, &mut self.op) } == 1 {
+                if self.stream_started {
+                    return Some(&mut self.op);
+                }
+            }
+
+            if unsafe { ogg_sync_pageout(&mut self.oy, &mut self.og) } != 1 {
+                return None;
+            }
+
+            if !self.stream_started {
+                let serial_no = unsafe { ogg_page_serialno(&mut self.og) };
+                unsafe {
+                    ogg_stream_init(&mut self.os, serial_no);
+                }
+                self.stream_started = true;
+            }
+
+            unsafe {
+                ogg_stream_pagein(&mut self.os, &mut self.og);
+            }
+
+        }
+
+    }
+
+    fn destroy(&mut self) {
+        unsafe {
+            if self.stream_started {
+                ogg_stream_clear(&mut self.os);
+            }
+            ogg_sync_clear(&mut self.oy);
+        }
+    }
+
+}
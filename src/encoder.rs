@@ -51,6 +51,37 @@ use vorbisenc_sys::{
     vorbis_encode_init_vbr
 };
 
+// Capped-VBR needs the staged `setup_vbr` / ctl / `setup_init` sequence so
+// that `management_active` is set before the quality tables are picked;
+// none of `vorbis_encode_ctl`, `ovectl_ratemanage2_arg` or the staged setup
+// calls are exposed by `vorbisenc_sys` yet, so they're declared here
+// against the already-linked `libvorbisenc`.
+use libc::{c_int, c_long, c_double};
+
+const OV_ECTL_RATEMANAGE2_GET: c_int = 0x14;
+const OV_ECTL_RATEMANAGE2_SET: c_int = 0x15;
+
+#[repr(C)]
+struct OvEctlRatemanage2Arg {
+    management_active: c_int,
+
+    bitrate_limit_min_kbps: c_long,
+    bitrate_limit_max_kbps: c_long,
+    bitrate_limit_reservoir_bits: c_long,
+    bitrate_limit_reservoir_bias: c_double,
+
+    bitrate_average_kbps: c_long,
+    bitrate_average_damping: c_double,
+
+    slew_damping: c_double
+}
+
+extern "C" {
+    fn vorbis_encode_ctl(vi: *mut vorbis_info, number: c_int, arg: *mut OvEctlRatemanage2Arg) -> c_int;
+    fn vorbis_encode_setup_vbr(vi: *mut vorbis_info, channels: c_long, rate: c_long, quality: f32) -> c_int;
+    fn vorbis_encode_setup_init(vi: *mut vorbis_info) -> c_int;
+}
+
 
 // Ogg Dependencies -----------------------------------------------------------
 use ogg_sys::{
@@ -78,31 +109,62 @@ enum EncoderState {
 
 // Simple Ogg Vorbis Encoder Implementation -----------------------------------
 
-/// Implementation of a file based ogg-vorbis audio encoder.
-pub struct OggVorbisEncoder {
-    file: File,
+/// Implementation of an ogg-vorbis audio encoder which is generic over its
+/// output sink.
+pub struct OggVorbisEncoder<W: Write> {
+    writer: W,
     ogg: Box<OggState>,
     vorbis: Box<VorbisState>,
     state: EncoderState,
     file_size: usize
 }
 
-impl OggVorbisEncoder {
+impl OggVorbisEncoder<File> {
 
     /// Creates a audio stream with the specified output file.
-    pub fn new(filename: &str) -> Result<OggVorbisEncoder, Error> {
+    pub fn new(filename: &str) -> Result<OggVorbisEncoder<File>, Error> {
         match File::create(filename) {
-            Ok(file) => Ok(OggVorbisEncoder {
-                file: file,
-                ogg: Box::new(OggState::new()),
-                vorbis: Box::new(VorbisState::new()),
-                state: EncoderState::Created,
-                file_size: 0
-            }),
+            Ok(file) => Ok(OggVorbisEncoder::from_writer(file)),
             Err(e) => Err(e)
         }
     }
 
+}
+
+impl<W: Write> OggVorbisEncoder<W> {
+
+    /// Creates a audio stream which writes into the specified `writer`,
+    /// e.g. a `TcpStream`, a `Vec<u8>` or any other `io::Write` sink.
+    pub fn from_writer(writer: W) -> OggVorbisEncoder<W> {
+        OggVorbisEncoder {
+            writer: writer,
+            ogg: Box::new(OggState::new()),
+            vorbis: Box::new(VorbisState::new()),
+            state: EncoderState::Created,
+            file_size: 0
+        }
+    }
+
+    /// Adds a `key`/`value` pair (e.g. `TITLE`, `ARTIST`, `COMMENT`) to the
+    /// Vorbis comment header. Must be called before the stream is
+    /// initialized.
+    pub fn set_comment(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match self.state {
+            EncoderState::Created => {
+                CString::new(key).map_err(|e| e.to_string())?;
+                CString::new(value).map_err(|e| e.to_string())?;
+                self.vorbis.comments.push((key.to_string(), value.to_string()));
+                Ok(())
+            },
+            EncoderState::Initialized => {
+                Err("Audio stream already initialized.".to_string())
+            },
+            EncoderState::Closed => {
+                Err("Audio stream already closed.".to_string())
+            }
+        }
+    }
+
     /// Initializes the audio stream for encoding with a pre-defined bitrate
     /// configuration.
     pub fn initialize(
@@ -125,7 +187,7 @@ impl OggVorbisEncoder {
                     min_bitrate.map_or(-1, |b| b as i64)
                 );
                 self.ogg.init(&mut self.vorbis);
-                self.ogg.write_flush(&mut self.file);
+                self.file_size += self.ogg.write_flush(&mut self.writer);
 
                 self.state = EncoderState::Initialized;
 
@@ -154,7 +216,46 @@ impl OggVorbisEncoder {
 
                 self.vorbis.init_vbr(channels, sample_rate as i64, quality);
                 self.ogg.init(&mut self.vorbis);
-                self.file_size += self.ogg.write_flush(&mut self.file);
+                self.file_size += self.ogg.write_flush(&mut self.writer);
+
+                self.state = EncoderState::Initialized;
+
+                Ok(())
+
+            },
+            EncoderState::Initialized => {
+                Err("Audio stream already initialized.".to_string())
+            },
+            EncoderState::Closed => {
+                Err("Audio stream already closed.".to_string())
+            }
+        }
+    }
+
+    /// Initializes the audio stream with a VBR quality target that is
+    /// additionally capped by a hard bitrate floor and ceiling, suitable
+    /// for bandwidth-limited streaming.
+    pub fn initialize_capped_vbr(
+        &mut self,
+        channels: usize,
+        sample_rate: u32,
+        quality: f32,
+        min_bitrate: u32,
+        max_bitrate: u32
+
+    ) -> Result<(), String> {
+        match self.state {
+            EncoderState::Created => {
+
+                self.vorbis.init_capped_vbr(
+                    channels,
+                    sample_rate as i64,
+                    quality,
+                    min_bitrate,
+                    max_bitrate
+                )?;
+                self.ogg.init(&mut self.vorbis);
+                self.file_size += self.ogg.write_flush(&mut self.writer);
 
                 self.state = EncoderState::Initialized;
 
@@ -177,8 +278,26 @@ impl OggVorbisEncoder {
                 Err("Audio stream not initialized.".to_string())
             },
             EncoderState::Initialized => {
-                self.vorbis.write_samples(samples);
-                self.file_size += self.ogg.write(&mut self.file, &mut self.vorbis);
+                self.vorbis.write_samples(samples)?;
+                self.file_size += self.ogg.write(&mut self.writer, &mut self.vorbis);
+                Ok(())
+            },
+            EncoderState::Closed => {
+                Err("Audio stream already closed.".to_string())
+            }
+        }
+    }
+
+    /// Writes the normalized `[-1.0, 1.0]` float `samples` into the audio
+    /// stream, skipping the lossy round-trip through `i16`.
+    pub fn write_float_samples(&mut self, samples: &[f32]) -> Result<(), String> {
+        match self.state {
+            EncoderState::Created => {
+                Err("Audio stream not initialized.".to_string())
+            },
+            EncoderState::Initialized => {
+                self.vorbis.write_float_samples(samples)?;
+                self.file_size += self.ogg.write(&mut self.writer, &mut self.vorbis);
                 Ok(())
             },
             EncoderState::Closed => {
@@ -195,7 +314,7 @@ impl OggVorbisEncoder {
             },
             EncoderState::Initialized => {
                 self.vorbis.close();
-                self.file_size += self.ogg.write(&mut self.file, &mut self.vorbis);
+                self.file_size += self.ogg.write(&mut self.writer, &mut self.vorbis);
                 self.state == EncoderState::Closed;
                 Ok(())
             },
@@ -212,7 +331,7 @@ impl OggVorbisEncoder {
 
 }
 
-impl Drop for OggVorbisEncoder {
+impl<W: Write> Drop for OggVorbisEncoder<W> {
     fn drop(&mut self) {
         self.ogg.destroy();
         self.vorbis.destroy();
@@ -227,7 +346,8 @@ struct VorbisState {
     vc: vorbis_comment,
     vd: vorbis_dsp_state,
     vb: vorbis_block,
-    channels: usize
+    channels: usize,
+    comments: Vec<(String, String)>
 }
 
 impl VorbisState {
@@ -238,7 +358,8 @@ impl VorbisState {
             vc: unsafe { mem::zeroed() },
             vd: unsafe { mem::zeroed() },
             vb: unsafe { mem::zeroed() },
-            channels: 0
+            channels: 0,
+            comments: Vec::new()
         }
     }
 
@@ -258,50 +379,118 @@ impl VorbisState {
         self.post_init();
     }
 
-    fn write_samples(&mut self, samples: &[i16]) {
+    fn init_capped_vbr(
+        &mut self,
+        channels: usize,
+        sample_rate: i64,
+        quality: f32,
+        min_bitrate: u32,
+        max_bitrate: u32
+
+    ) -> Result<(), String> {
+
+        self.pre_init(channels);
+
+        let mut ratemanage: OvEctlRatemanage2Arg = unsafe { mem::zeroed() };
+        let result = unsafe {
+            vorbis_encode_setup_vbr(&mut self.vi, channels as c_long, sample_rate as c_long, quality);
+            vorbis_encode_ctl(&mut self.vi, OV_ECTL_RATEMANAGE2_GET, &mut ratemanage);
+
+            ratemanage.management_active = 1;
+            ratemanage.bitrate_limit_min_kbps = (min_bitrate / 1000) as c_long;
+            ratemanage.bitrate_limit_max_kbps = (max_bitrate / 1000) as c_long;
+
+            vorbis_encode_ctl(&mut self.vi, OV_ECTL_RATEMANAGE2_SET, &mut ratemanage);
+
+            vorbis_encode_setup_init(&mut self.vi)
+        };
+
+        if result != 0 {
+            return Err("Failed to apply hard bitrate constraints.".to_string());
+        }
+
+        self.post_init();
+
+        Ok(())
+
+    }
+
+    fn write_samples(&mut self, samples: &[i16]) -> Result<(), String> {
+
+        if self.channels == 0 {
+            return Err("Audio stream has no channels.".to_string());
+        }
 
-        let len = samples.len();
+        if samples.len() % self.channels != 0 {
+            return Err("Sample buffer length is not a multiple of the channel count.".to_string());
+        }
+
+        let frames = samples.len() / self.channels;
         let channel_buffers = unsafe {
             std::slice::from_raw_parts(
-                vorbis_analysis_buffer(&mut self.vd, len as i32),
+                vorbis_analysis_buffer(&mut self.vd, frames as i32),
                 self.channels
             )
         };
 
-        if self.channels == 1 {
+        for c in 0..self.channels {
 
-            let mono_ptr: *mut f32 = channel_buffers[0];
-            let mono: &mut [f32] = unsafe {
-                std::slice::from_raw_parts_mut(mono_ptr, len)
+            let channel_ptr: *mut f32 = channel_buffers[c];
+            let channel: &mut [f32] = unsafe {
+                std::slice::from_raw_parts_mut(channel_ptr, frames)
             };
 
-            for i in 0..len {
-                mono[i] = samples[i] as f32 / 32768.0;
+            for i in 0..frames {
+                channel[i] = samples[i * self.channels + c] as f32 / 32768.0;
             }
 
-        } else if self.channels == 2 {
+        }
 
-            let left_ptr: *mut f32 = channel_buffers[0];
-            let left: &mut [f32] = unsafe {
-                std::slice::from_raw_parts_mut(left_ptr, len)
-            };
+        unsafe {
+            vorbis_analysis_wrote(&mut self.vd, frames as i32);
+        }
+
+        Ok(())
+
+    }
+
+    fn write_float_samples(&mut self, samples: &[f32]) -> Result<(), String> {
+
+        if self.channels == 0 {
+            return Err("Audio stream has no channels.".to_string());
+        }
+
+        if samples.len() % self.channels != 0 {
+            return Err("Sample buffer length is not a multiple of the channel count.".to_string());
+        }
+
+        let frames = samples.len() / self.channels;
+        let channel_buffers = unsafe {
+            std::slice::from_raw_parts(
+                vorbis_analysis_buffer(&mut self.vd, frames as i32),
+                self.channels
+            )
+        };
 
-            let right_ptr: *mut f32 = channel_buffers[1];
-            let right: &mut [f32] = unsafe {
-                std::slice::from_raw_parts_mut(right_ptr, len)
+        for c in 0..self.channels {
+
+            let channel_ptr: *mut f32 = channel_buffers[c];
+            let channel: &mut [f32] = unsafe {
+                std::slice::from_raw_parts_mut(channel_ptr, frames)
             };
 
-            for i in 0..len / 2 {
-                left[i] = samples[i * 2] as f32 / 32768.0;
-                right[i] = samples[i * 2 + 1] as f32 / 32768.0;
+            for i in 0..frames {
+                channel[i] = samples[i * self.channels + c];
             }
 
         }
 
         unsafe {
-            vorbis_analysis_wrote(&mut self.vd, (len / self.channels) as i32);
+            vorbis_analysis_wrote(&mut self.vd, frames as i32);
         }
 
+        Ok(())
+
     }
 
     fn close(&mut self) {
@@ -320,6 +509,14 @@ impl VorbisState {
                 CString::new("ENCODER").unwrap().as_ptr(),
                 CString::new("vorbis_enc.rs").unwrap().as_ptr()
             );
+
+            for &(ref key, ref value) in &self.comments {
+                vorbis_comment_add_tag(
+                    &mut self.vc,
+                    CString::new(key.as_str()).unwrap().as_ptr(),
+                    CString::new(value.as_str()).unwrap().as_ptr()
+                );
+            }
         }
     }
 
@@ -386,7 +583,7 @@ impl OggState {
 
     }
 
-    fn write(&mut self, file: &mut File, vorbis: &mut VorbisState) -> usize {
+    fn write<W: Write>(&mut self, writer: &mut W, vorbis: &mut VorbisState) -> usize {
 
         let null = ptr::null_mut();
         let mut bytes_written = 0;
@@ -404,7 +601,7 @@ impl OggState {
                     ogg_stream_packetin(&mut self.os, &mut self.op);
                 }
 
-                bytes_written += self.write_page(file);
+                bytes_written += self.write_page(writer);
 
             }
 
@@ -414,7 +611,7 @@ impl OggState {
 
     }
 
-    fn write_page(&mut self, file: &mut File) -> usize {
+    fn write_page<W: Write>(&mut self, writer: &mut W) -> usize {
 
         let mut bytes_written = 0;
 
@@ -430,8 +627,8 @@ impl OggState {
             } else {
                 let header: &[u8] = unsafe { std::slice::from_raw_parts(self.og.header, self.og.header_len as usize) };
                 let body: &[u8] = unsafe { std::slice::from_raw_parts(self.og.body, self.og.body_len as usize) };
-                file.write_all(header).ok();
-                file.write_all(body).ok();
+                writer.write_all(header).ok();
+                writer.write_all(body).ok();
                 bytes_written += self.og.header_len as usize;
                 bytes_written += self.og.body_len as usize;
 
@@ -447,7 +644,7 @@ impl OggState {
 
     }
 
-    fn write_flush(&mut self, file: &mut File) -> usize {
+    fn write_flush<W: Write>(&mut self, writer: &mut W) -> usize {
 
         let mut bytes_written = 0;
         loop {
@@ -462,8 +659,8 @@ impl OggState {
             } else {
                 let header: &[u8] = unsafe { std::slice::from_raw_parts(self.og.header, self.og.header_len as usize) };
                 let body: &[u8] = unsafe { std::slice::from_raw_parts(self.og.body, self.og.body_len as usize) };
-                file.write_all(header).ok();
-                file.write_all(body).ok();
+                writer.write_all(header).ok();
+                writer.write_all(body).ok();
                 bytes_written += self.og.header_len as usize;
                 bytes_written += self.og.body_len as usize;
             }
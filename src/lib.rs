@@ -17,5 +17,7 @@ extern crate libc;
 
 // Exports --------------------------------------------------------------------
 mod encoder;
+mod decoder;
 pub use encoder::OggVorbisEncoder;
+pub use decoder::OggVorbisDecoder;
 